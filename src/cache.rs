@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use hyper::body::Bytes;
+
+/// Caches template file bytes read from disk, keyed by their resolved
+/// path, so repeated requests for the same error page are served from
+/// memory instead of re-reading the file every time. Entries are dropped
+/// by the templates-dir watcher in `main` when the underlying file
+/// changes, so edits take effect without restarting the pod.
+#[derive(Debug, Default)]
+pub struct FileCache {
+    entries: RwLock<HashMap<PathBuf, Bytes>>,
+}
+
+impl FileCache {
+    /// Returns the cached bytes for `path`, reading and caching them from
+    /// disk on a miss.
+    pub fn get_or_read(&self, path: &Path) -> io::Result<Bytes> {
+        if let Some(bytes) = self.entries.read().unwrap().get(path) {
+            return Ok(bytes.clone());
+        }
+
+        let bytes = Bytes::from(fs::read(path)?);
+        self.entries.write().unwrap().insert(path.to_path_buf(), bytes.clone());
+        Ok(bytes)
+    }
+
+    /// Drops the cached bytes for `path`, if any, so the next lookup
+    /// re-reads the file from disk.
+    pub fn invalidate(&self, path: &Path) {
+        self.entries.write().unwrap().remove(path);
+    }
+
+    /// Drops every cached entry, used when a filesystem event doesn't
+    /// name a specific path.
+    pub fn clear(&self) {
+        self.entries.write().unwrap().clear();
+    }
+}
@@ -0,0 +1,92 @@
+/// Pre-compressed template variants this server knows how to serve,
+/// mirroring the encodings tower-http's fs service negotiates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// The file extension appended to a template path for this encoding's
+    /// pre-compressed sibling, e.g. `500.html.br`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    /// The value to send in the `Content-Encoding` response header.
+    pub fn header_value(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// Extracts the `q` value from a media-range/coding's `;`-separated
+/// pieces (everything after the coding itself), defaulting to `1.0`.
+fn q_value<'a>(pieces: impl Iterator<Item = &'a str>) -> f32 {
+    pieces
+        .find_map(|p| p.trim().strip_prefix("q="))
+        .and_then(|q| q.trim().parse::<f32>().ok())
+        .unwrap_or(1.0)
+}
+
+/// Parses an `Accept-Encoding` header value into `(encoding, q)` pairs for
+/// codings this server has pre-compressed variants for, discarding
+/// anything this server doesn't support and any pair with `q=0`.
+fn parse_accept_encoding(header: &str) -> Vec<(Encoding, f32)> {
+    header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.trim().split(';');
+            let encoding = match pieces.next()?.trim() {
+                "br" => Encoding::Brotli,
+                "gzip" => Encoding::Gzip,
+                _ => return None,
+            };
+
+            let q = q_value(pieces);
+            if q <= 0.0 {
+                None
+            } else {
+                Some((encoding, q))
+            }
+        })
+        .collect()
+}
+
+/// The `q` the client assigned to `identity`, defaulting to `1.0` (per
+/// RFC 7231) when the header doesn't mention it explicitly.
+fn identity_q(header: &str) -> f32 {
+    header
+        .split(',')
+        .find_map(|part| {
+            let mut pieces = part.trim().split(';');
+            if pieces.next()?.trim() == "identity" {
+                Some(q_value(pieces))
+            } else {
+                None
+            }
+        })
+        .unwrap_or(1.0)
+}
+
+/// Picks the highest-q encoding the server supports among `br`, `gzip`,
+/// and identity, preferring a compressed coding on a tie. Returns `None`
+/// — meaning identity, the uncompressed template — when the header is
+/// absent, names nothing supported, or identity's `q` beats every
+/// compressed coding's.
+pub fn negotiate(header: &str) -> Option<Encoding> {
+    let (encoding, q) = parse_accept_encoding(header)
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))?;
+
+    if q >= identity_q(header) {
+        Some(encoding)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,45 @@
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::HeaderMap;
+
+/// Weak validators for a template file, borrowing the approach tower-http's
+/// fs service uses: an `ETag` derived from size and modification time, plus
+/// the modification time itself for `Last-Modified`.
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    /// Computes validators from file metadata, or `None` if the
+    /// filesystem can't report a modification time.
+    pub fn from_metadata(metadata: &Metadata) -> Option<Self> {
+        let secs = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        // HTTP dates carry whole seconds, so truncate to match what
+        // `Last-Modified` sends and `If-Modified-Since` echoes back.
+        let last_modified = UNIX_EPOCH + Duration::from_secs(secs);
+        let etag = format!("W/\"{:x}-{:x}\"", metadata.len(), secs);
+        Some(Self { etag, last_modified })
+    }
+
+    /// Whether `headers` carry a conditional-request header already
+    /// satisfied by these validators, meaning the caller can answer with
+    /// `304 Not Modified` instead of the body.
+    pub fn satisfied_by(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.get(hyper::header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+            return if_none_match.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag == self.etag
+            });
+        }
+
+        if let Some(if_modified_since) = headers.get(hyper::header::IF_MODIFIED_SINCE).and_then(|v| v.to_str().ok()) {
+            if let Ok(since) = httpdate::parse_http_date(if_modified_since) {
+                return self.last_modified <= since;
+            }
+        }
+
+        false
+    }
+}
@@ -1,19 +1,35 @@
-use std::fs::OpenOptions;
-use std::io::{BufReader, Read};
+use std::fs;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::str::FromStr;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use clap::{App, Arg, ArgMatches};
 use futures_util::future;
 use hyper::{Body, Request, Response, Server};
 use hyper::service::Service;
-use mime::Mime;
+use notify::Watcher;
+use serde_json::json;
+
+use cache::FileCache;
+use conditional::Validators;
+use metrics::Metrics;
+use negotiation::DEFAULT_FORMAT;
+use templates::TemplateCache;
+
+mod cache;
+mod compression;
+mod conditional;
+mod metrics;
+mod negotiation;
+mod templates;
 
 const ROOT: &str = "/";
 
+/// The path telemetry is served on, alongside the error-page API.
+const METRICS_PATH: &str = "/metrics";
+
 /// The name of the header used as source of the HTTP status code to return
 const CODE_HEADER: &str = "X-Code";
 
@@ -21,19 +37,67 @@ const CODE_HEADER: &str = "X-Code";
 /// the Accept header sent by the client.
 const FORMAT_HEADER: &str = "X-Format";
 
-/// The format that will be used by default if the FORMAT_HEADER is not specified
-const DEFAULT_FORMAT: &str = "html";
-
 const DEFAULT_CODE: u32 = 404;
 
+/// Headers ingress-nginx's default-backend passes along with the error,
+/// exposed to templates as Handlebars variables of the same name.
+const CONTEXT_HEADERS: &[&str] = &[
+    "X-Original-URI",
+    "X-Namespace",
+    "X-Ingress-Name",
+    "X-Service-Name",
+    "X-Service-Port",
+    "X-Request-ID",
+];
+
+/// Appends an extra extension to a path, e.g. `files/500.html` + `br` ->
+/// `files/500.html.br`, for locating pre-compressed template siblings.
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Builds the Handlebars rendering context from the resolved code and the
+/// ingress-nginx default-backend headers present on the request.
+fn template_context(req: &Request<Body>, code: u32) -> serde_json::Value {
+    let mut context = json!({ "code": code });
+    let map = context.as_object_mut().unwrap();
+    for header in CONTEXT_HEADERS {
+        let value = req.headers().get(*header)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        map.insert(header.to_string(), json!(value));
+    }
+    context
+}
+
 #[derive(Debug)]
 pub struct Svc {
     templates_dir: PathBuf,
+    metrics: Arc<Metrics>,
+    templates: Arc<TemplateCache>,
+    file_cache: Arc<FileCache>,
 }
 
 impl Svc {
     pub fn new(templates_dir: PathBuf) -> Self {
-        Self { templates_dir }
+        Self::with_shared_state(
+            templates_dir,
+            Arc::new(Metrics::default()),
+            Arc::new(TemplateCache::default()),
+            Arc::new(FileCache::default()),
+        )
+    }
+
+    fn with_shared_state(
+        templates_dir: PathBuf,
+        metrics: Arc<Metrics>,
+        templates: Arc<TemplateCache>,
+        file_cache: Arc<FileCache>,
+    ) -> Self {
+        Self { templates_dir, metrics, templates, file_cache }
     }
 }
 
@@ -50,6 +114,15 @@ impl Service<Request<Body>> for Svc {
         let rsp = Response::builder();
 
         let uri = req.uri();
+        if uri.path() == METRICS_PATH {
+            let body = Body::from(self.metrics.render());
+            let rsp = rsp.status(200)
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(body)
+                .unwrap();
+            return future::ok(rsp);
+        }
+
         if uri.path() != ROOT {
             let body = Body::from(Vec::new());
             let rsp = rsp.status(404).body(body).unwrap();
@@ -70,37 +143,96 @@ impl Service<Request<Body>> for Svc {
                 .unwrap_or(DEFAULT_CODE))
             .unwrap_or(DEFAULT_CODE);
 
-        let response = req.headers().get(FORMAT_HEADER)
-            .map(|value| match value.to_str() {
-                Ok(ct) => {
-                    match Mime::from_str(ct) {
-                        Ok(mime) => {
-                            format!("{}.{}", code, mime.subtype())
-                        }
+        // Parse the Accept-style X-Format header into media ranges sorted by
+        // q-value, and pick the first one a template actually exists for.
+        let ranges = req.headers().get(FORMAT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(negotiation::parse)
+            .filter(|ranges| !ranges.is_empty());
+
+        let selected = ranges.as_ref().and_then(|ranges| ranges.iter().find_map(|range| {
+            let format = negotiation::format_for(&range.mime);
+            let path = self.templates_dir.join(format!("{}.{}", code, format));
+            if path.exists() {
+                Some((format, path, negotiation::content_type_for(&range.mime)))
+            } else {
+                None
+            }
+        }));
+
+        let (format, template_path, content_type) = selected.unwrap_or_else(|| {
+            let format = DEFAULT_FORMAT.to_string();
+            let path = self.templates_dir.join(format!("{}.{}", code, format));
+            (format, path, format!("text/{}", DEFAULT_FORMAT))
+        });
+
+        self.metrics.record_request(code, &format);
+
+        let encoding = req.headers().get(hyper::header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .and_then(compression::negotiate);
+
+        let (open_path, encoding) = match encoding {
+            Some(encoding) => {
+                let candidate = append_extension(&template_path, encoding.extension());
+                if candidate.exists() {
+                    (candidate, Some(encoding))
+                } else {
+                    (template_path, None)
+                }
+            }
+            None => (template_path, None),
+        };
+
+        // Only the pre-compressed variant is served as-is; the identity
+        // path is re-rendered per request with request-specific Handlebars
+        // context, so file-metadata validators (and 304s) don't apply to it.
+        let validators = encoding.and_then(|_| fs::metadata(&open_path).ok())
+            .and_then(|metadata| Validators::from_metadata(&metadata));
+
+        if let Some(validators) = &validators {
+            if validators.satisfied_by(req.headers()) {
+                let rsp = rsp.status(304)
+                    .header(hyper::header::ETAG, validators.etag.as_str())
+                    .header(hyper::header::LAST_MODIFIED, httpdate::fmt_http_date(validators.last_modified))
+                    .body(Body::empty())
+                    .unwrap();
+                return future::ok(rsp);
+            }
+        }
+
+        return future::ok(match self.file_cache.get_or_read(&open_path) {
+            Ok(buffer) => {
+                // Pre-compressed variants are served as-is; only the
+                // uncompressed template is rendered through Handlebars.
+                let body = if encoding.is_none() {
+                    let source = String::from_utf8_lossy(&buffer);
+                    let context = template_context(&req, code);
+                    match self.templates.render(&open_path, &source, &context) {
+                        Ok(rendered) => Body::from(rendered),
                         Err(error) => {
-                            eprintln!("Unexpected error reading the media type: {}. Using {}", error, DEFAULT_FORMAT);
-                            format!("{}.{}", code, DEFAULT_FORMAT)
+                            eprintln!("Unexpected error rendering template {:?}: {}", &open_path, error);
+                            Body::from(buffer)
                         }
                     }
+                } else {
+                    Body::from(buffer)
+                };
+
+                let mut rsp = rsp.status(200).header(hyper::header::CONTENT_TYPE, content_type.as_str());
+                if let Some(encoding) = encoding {
+                    rsp = rsp.header(hyper::header::CONTENT_ENCODING, encoding.header_value())
+                        .header(hyper::header::VARY, "Accept-Encoding");
                 }
-                Err(_) => {
-                    format!("{}.{}", code, DEFAULT_FORMAT)
+                if let Some(validators) = &validators {
+                    rsp = rsp.header(hyper::header::ETAG, validators.etag.as_str())
+                        .header(hyper::header::LAST_MODIFIED, httpdate::fmt_http_date(validators.last_modified));
                 }
-            })
-            .unwrap_or(format!("{}.{}", code, DEFAULT_FORMAT));
-
-        self.templates_dir.push(response);
-        return future::ok(match OpenOptions::new().read(true).open(&self.templates_dir) {
-            Ok(file) => {
-                let mut reader = BufReader::new(file);
-                let mut buffer = Vec::new();
-                reader.read_to_end(&mut buffer).unwrap();
-
-                let body = Body::from(buffer);
-                rsp.status(200).body(body).unwrap()
+                rsp.body(body).unwrap()
             }
             Err(error) => {
-                eprintln!("Unexpected error reading the template file {:?}: {}", &self.templates_dir, error);
+                eprintln!("Unexpected error reading the template file {:?}: {}", &open_path, error);
+                self.metrics.record_template_miss();
                 let body = Body::from(Vec::new());
                 rsp.status(404).body(body).unwrap()
             }
@@ -110,11 +242,26 @@ impl Service<Request<Body>> for Svc {
 
 pub struct MakeSvc {
     templates_dir: PathBuf,
+    metrics: Arc<Metrics>,
+    templates: Arc<TemplateCache>,
+    file_cache: Arc<FileCache>,
 }
 
 impl MakeSvc {
     pub fn new(templates_dir: PathBuf) -> Self {
-        Self { templates_dir }
+        Self::with_shared_state(templates_dir, Arc::new(TemplateCache::default()), Arc::new(FileCache::default()))
+    }
+
+    /// Builds a `MakeSvc` around existing template and file caches, so
+    /// `main` can keep a handle to them for the templates-dir watcher to
+    /// invalidate.
+    fn with_shared_state(templates_dir: PathBuf, templates: Arc<TemplateCache>, file_cache: Arc<FileCache>) -> Self {
+        Self {
+            templates_dir,
+            metrics: Arc::new(Metrics::default()),
+            templates,
+            file_cache,
+        }
     }
 }
 
@@ -128,7 +275,12 @@ impl<T> Service<T> for MakeSvc {
     }
 
     fn call(&mut self, _: T) -> Self::Future {
-        future::ok(Svc::new(self.templates_dir.clone()))
+        future::ok(Svc::with_shared_state(
+            self.templates_dir.clone(),
+            self.metrics.clone(),
+            self.templates.clone(),
+            self.file_cache.clone(),
+        ))
     }
 }
 
@@ -177,11 +329,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         exit(1)
     }
 
-    let server = Server::bind(&addr).serve(MakeSvc::new(templates_dir));
+    // The watcher reports absolute paths, so make the cache keys absolute
+    // too or invalidation would never match a relative templates-dir.
+    let templates_dir = std::env::current_dir()?.join(templates_dir);
+
+    let templates = Arc::new(TemplateCache::default());
+    let file_cache = Arc::new(FileCache::default());
+
+    // Watch templates-dir for edits so caches stay fresh without
+    // requiring the pod to restart. The watcher is kept alive for the
+    // lifetime of `main` by holding on to it until after `server.await`.
+    let watch_templates = templates.clone();
+    let watch_file_cache = file_cache.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            Ok(event) if event.paths.is_empty() => {
+                watch_file_cache.clear();
+                watch_templates.clear();
+            }
+            Ok(event) => {
+                for path in &event.paths {
+                    watch_file_cache.invalidate(path);
+                    watch_templates.invalidate(path);
+                }
+            }
+            Err(error) => eprintln!("Unexpected error watching the templates directory: {}", error),
+        }
+    })?;
+    watcher.watch(&templates_dir, notify::RecursiveMode::Recursive)?;
+
+    let server = Server::bind(&addr).serve(MakeSvc::with_shared_state(templates_dir, templates, file_cache));
 
     println!("Listening on http://{}", addr);
 
     server.await?;
+    drop(watcher);
 
     Ok(())
 }
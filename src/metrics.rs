@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Tracks counters describing how error pages have been served so far.
+///
+/// A single instance is shared (via `Arc`) across every `Svc` minted by
+/// `MakeSvc`, since a fresh `Svc` is created per connection.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests_total: AtomicU64,
+    requests_by_code: Mutex<HashMap<u32, u64>>,
+    requests_by_format: Mutex<HashMap<String, u64>>,
+    template_misses_total: AtomicU64,
+}
+
+impl Metrics {
+    /// Records a served request, labeling it with the resolved HTTP code
+    /// and negotiated format.
+    pub fn record_request(&self, code: u32, format: &str) {
+        self.requests_total.fetch_add(1, Ordering::Relaxed);
+        *self.requests_by_code.lock().unwrap().entry(code).or_insert(0) += 1;
+        *self.requests_by_format.lock().unwrap().entry(format.to_string()).or_insert(0) += 1;
+    }
+
+    /// Records a template-file lookup that fell through to the empty 404.
+    pub fn record_template_miss(&self) {
+        self.template_misses_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the current counters in Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP ingress_nginx_errors_requests_total Total number of requests served.\n");
+        out.push_str("# TYPE ingress_nginx_errors_requests_total counter\n");
+        out.push_str(&format!("ingress_nginx_errors_requests_total {}\n", self.requests_total.load(Ordering::Relaxed)));
+
+        out.push_str("# HELP ingress_nginx_errors_requests_by_code_total Requests served, labeled by resolved HTTP code.\n");
+        out.push_str("# TYPE ingress_nginx_errors_requests_by_code_total counter\n");
+        for (code, count) in self.requests_by_code.lock().unwrap().iter() {
+            out.push_str(&format!("ingress_nginx_errors_requests_by_code_total{{code=\"{}\"}} {}\n", code, count));
+        }
+
+        out.push_str("# HELP ingress_nginx_errors_requests_by_format_total Requests served, labeled by negotiated format.\n");
+        out.push_str("# TYPE ingress_nginx_errors_requests_by_format_total counter\n");
+        for (format, count) in self.requests_by_format.lock().unwrap().iter() {
+            out.push_str(&format!("ingress_nginx_errors_requests_by_format_total{{format=\"{}\"}} {}\n", format, count));
+        }
+
+        out.push_str("# HELP ingress_nginx_errors_template_misses_total Template lookups that fell through to the empty 404.\n");
+        out.push_str("# TYPE ingress_nginx_errors_template_misses_total counter\n");
+        out.push_str(&format!("ingress_nginx_errors_template_misses_total {}\n", self.template_misses_total.load(Ordering::Relaxed)));
+
+        out
+    }
+}
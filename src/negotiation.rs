@@ -0,0 +1,58 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use mime::Mime;
+
+/// The default format used when a request carries no `X-Format` header,
+/// or when negotiation doesn't turn up a matching template.
+pub const DEFAULT_FORMAT: &str = "html";
+
+/// A single entry of an `Accept`-style header: a media range and its
+/// relative quality.
+pub struct MediaRange {
+    pub mime: Mime,
+    pub q: f32,
+}
+
+/// Parses a comma-separated list of media ranges with optional q-values
+/// (e.g. `text/html,application/json;q=0.9,*/*;q=0.1`), sorted by q
+/// descending so callers can try the most preferred range first.
+pub fn parse(header: &str) -> Vec<MediaRange> {
+    let mut ranges: Vec<MediaRange> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.trim().split(';');
+            let mime = Mime::from_str(pieces.next()?.trim()).ok()?;
+
+            let q = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            Some(MediaRange { mime, q })
+        })
+        .collect();
+
+    ranges.sort_by(|a, b| b.q.partial_cmp(&a.q).unwrap_or(Ordering::Equal));
+    ranges
+}
+
+/// The template file subtype a media range resolves to, mapping `*/*` to
+/// the `DEFAULT_FORMAT`.
+pub fn format_for(mime: &Mime) -> String {
+    if *mime == mime::STAR_STAR {
+        DEFAULT_FORMAT.to_string()
+    } else {
+        mime.subtype().to_string()
+    }
+}
+
+/// The `Content-Type` to send for a media range, mapping `*/*` to
+/// `text/DEFAULT_FORMAT`.
+pub fn content_type_for(mime: &Mime) -> String {
+    if *mime == mime::STAR_STAR {
+        format!("text/{}", DEFAULT_FORMAT)
+    } else {
+        format!("{}/{}", mime.type_(), mime.subtype())
+    }
+}
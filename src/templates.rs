@@ -0,0 +1,39 @@
+use std::path::Path;
+use std::sync::RwLock;
+
+use handlebars::Handlebars;
+use serde_json::Value;
+
+/// Caches compiled Handlebars templates keyed by their source path, so a
+/// template file is parsed once (as in PTTH's file server) and reused
+/// across requests instead of being recompiled on every render.
+#[derive(Debug, Default)]
+pub struct TemplateCache {
+    registry: RwLock<Handlebars<'static>>,
+}
+
+impl TemplateCache {
+    /// Renders the template at `path` against `context`, compiling and
+    /// caching `source` under `path` the first time it's seen.
+    pub fn render(&self, path: &Path, source: &str, context: &Value) -> Result<String, handlebars::RenderError> {
+        let name = path.to_string_lossy().into_owned();
+
+        if !self.registry.read().unwrap().has_template(&name) {
+            self.registry.write().unwrap().register_template_string(&name, source)?;
+        }
+
+        self.registry.read().unwrap().render(&name, context)
+    }
+
+    /// Drops the compiled template for `path`, if any, so the next
+    /// render recompiles it from the (now-changed) file on disk.
+    pub fn invalidate(&self, path: &Path) {
+        self.registry.write().unwrap().unregister_template(&path.to_string_lossy());
+    }
+
+    /// Drops every compiled template, used when a filesystem event
+    /// doesn't name a specific path.
+    pub fn clear(&self) {
+        self.registry.write().unwrap().clear_templates();
+    }
+}